@@ -1,15 +1,20 @@
 // logger.rs
 
 use chrono::{Datelike, Local};
-use log::{kv::Key, Level, Log, Metadata, Record};
+use log::{
+    kv::{self, Key},
+    Level, Log, Metadata, Record,
+};
 use once_cell::sync::Lazy;
+use regex::Regex;
 
 use std::{
     collections::{HashMap, VecDeque},
-    fs::{File, OpenOptions},
+    fs::{self, File, OpenOptions},
     io::{BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Mutex,
+    time::{Duration, SystemTime},
 };
 
 const MAX_CACHE_SIZE: usize = 32;
@@ -18,16 +23,198 @@ static FILE_CACHE: Lazy<Mutex<FileCache>> =
     Lazy::new(|| Mutex::new(FileCache::new(MAX_CACHE_SIZE)));
 
 pub fn init_logger(stdout_level: log::LevelFilter, file_level: log::LevelFilter, base_path: impl Into<PathBuf>) {
-    LOGGER.set_base_path(base_path.into());
+    init_logger_with_retention(stdout_level, file_level, base_path, None);
+}
+
+/// Like [`init_logger`], but also prunes any `log_*.log`/`order_*.log` file under
+/// `base_path` whose last modification is older than `retention` as soon as the
+/// logger is installed.
+pub fn init_logger_with_retention(
+    stdout_level: log::LevelFilter,
+    file_level: log::LevelFilter,
+    base_path: impl Into<PathBuf>,
+    retention: Option<Duration>,
+) {
+    init_logger_with_format(stdout_level, file_level, base_path, retention, LogFormat::Text);
+}
+
+/// Like [`init_logger_with_retention`], but also selects the file output
+/// `format` up front instead of requiring a separate, order-sensitive
+/// [`set_log_format`] call after init.
+pub fn init_logger_with_format(
+    stdout_level: log::LevelFilter,
+    file_level: log::LevelFilter,
+    base_path: impl Into<PathBuf>,
+    retention: Option<Duration>,
+    format: LogFormat,
+) {
+    init_logger_with_options(stdout_level, file_level, base_path, retention, format, None);
+}
+
+/// Like [`init_logger_with_format`], but also configures the duplicate-line
+/// suppression window up front (see [`set_dedup_window`]) instead of
+/// requiring a separate, order-sensitive call after init.
+pub fn init_logger_with_options(
+    stdout_level: log::LevelFilter,
+    file_level: log::LevelFilter,
+    base_path: impl Into<PathBuf>,
+    retention: Option<Duration>,
+    format: LogFormat,
+    dedup_window: Option<usize>,
+) {
+    let base_path = base_path.into();
+    LOGGER.set_base_path(base_path.clone());
     LOGGER.set_levels(stdout_level, file_level);
+    LOGGER.set_format(format);
+    FILE_CACHE.lock().unwrap().dedup_window = dedup_window;
     log::set_logger(&*LOGGER).unwrap();
     log::set_max_level(stdout_level.max(file_level));
+    LOGGER.recompute_max_level();
+
+    if let Some(max_age) = retention {
+        cleanup_logs_at(&base_path, max_age);
+    }
+}
+
+/// Deletes stale `log_*.log`/`order_*.log` files under the logger's configured
+/// `base_path` whose last modification is older than `max_age`.
+///
+/// A single unreadable file never aborts the sweep: entries we can't stat or
+/// remove are silently skipped.
+pub fn cleanup_logs(max_age: Duration) {
+    let Some(base_path) = LOGGER.get_base_path() else {
+        return;
+    };
+    cleanup_logs_at(&base_path, max_age);
+}
+
+fn cleanup_logs_at(base_path: &Path, max_age: Duration) {
+    let Ok(entries) = fs::read_dir(base_path) else {
+        return;
+    };
+
+    let now = SystemTime::now();
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(file_name) = path.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
+
+        if !is_log_file(file_name) {
+            continue;
+        }
+
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        let Ok(modified) = metadata.modified() else {
+            continue;
+        };
+
+        let Ok(age) = now.duration_since(modified) else {
+            continue;
+        };
+
+        if age < max_age {
+            continue;
+        }
+
+        FILE_CACHE.lock().unwrap().evict(&path);
+        let _ = fs::remove_file(&path);
+    }
+}
+
+/// Caps total bytes under `base_path` held by `log_*.log`/`order_*.log` files,
+/// deleting the least-recently-modified ones first until the budget is met.
+///
+/// Complements [`MAX_CACHE_SIZE`], which only bounds the number of open file
+/// handles, not bytes on disk.
+pub fn enforce_disk_budget(max_disk_usage: u64) {
+    let Some(base_path) = LOGGER.get_base_path() else {
+        return;
+    };
+    enforce_disk_budget_at(&base_path, max_disk_usage, &[]);
+}
+
+/// `protected` paths are counted towards the total but are never candidates
+/// for eviction, e.g. files a log record just wrote to: their mtime is freshly
+/// bumped and would otherwise look like the newest file an instant before it
+/// looks like the oldest once a sibling write for the same record follows it.
+fn enforce_disk_budget_at(base_path: &Path, max_disk_usage: u64, protected: &[PathBuf]) {
+    let Ok(entries) = fs::read_dir(base_path) else {
+        return;
+    };
+
+    let mut files = Vec::new();
+    let mut total: u64 = 0;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(file_name) = path.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
+
+        if !is_log_file(file_name) {
+            continue;
+        }
+
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        let Ok(modified) = metadata.modified() else {
+            continue;
+        };
+
+        total += metadata.len();
+        if !protected.contains(&path) {
+            files.push((path, metadata.len(), modified));
+        }
+    }
+
+    if total <= max_disk_usage {
+        return;
+    }
+
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    for (path, size, _) in files {
+        if total <= max_disk_usage {
+            break;
+        }
+
+        FILE_CACHE.lock().unwrap().evict(&path);
+        if fs::remove_file(&path).is_ok() {
+            total = total.saturating_sub(size);
+        }
+    }
+}
+
+/// True for a live `log_*.log`/`order_*.log` file or one of its rotated
+/// backups (`log_*.log.1`, `log_*.log.2`, ...) produced by [`FileCache::rotate`],
+/// so retention/budget sweeps don't leave backups accumulating forever.
+fn is_log_file(file_name: &str) -> bool {
+    let base = strip_rotation_suffix(file_name);
+    (base.starts_with("log_") || base.starts_with("order_")) && base.ends_with(".log")
+}
+
+/// Strips a trailing `.N` rotation suffix (as added by [`rotated_path`]), if any.
+fn strip_rotation_suffix(file_name: &str) -> &str {
+    match file_name.rsplit_once('.') {
+        Some((base, suffix)) if !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()) => base,
+        _ => file_name,
+    }
 }
 
 pub struct DailyLogger {
     base_path: Mutex<Option<PathBuf>>,
     stdout_level: Mutex<log::LevelFilter>,
     file_level: Mutex<log::LevelFilter>,
+    max_disk_usage: Mutex<Option<u64>>,
+    format: Mutex<LogFormat>,
+    target_filters: Mutex<Vec<(TargetPattern, log::LevelFilter)>>,
 }
 
 impl DailyLogger {
@@ -36,6 +223,9 @@ impl DailyLogger {
             base_path: Mutex::new(None),
             stdout_level: Mutex::new(log::LevelFilter::Info),
             file_level: Mutex::new(log::LevelFilter::Info),
+            max_disk_usage: Mutex::new(None),
+            format: Mutex::new(LogFormat::Text),
+            target_filters: Mutex::new(Vec::new()),
         }
     }
 
@@ -51,12 +241,134 @@ impl DailyLogger {
     fn set_levels(&self, stdout_level: log::LevelFilter, file_level: log::LevelFilter) {
         *self.stdout_level.lock().unwrap() = stdout_level;
         *self.file_level.lock().unwrap() = file_level;
+        self.recompute_max_level();
+    }
+
+    fn set_max_disk_usage(&self, max_disk_usage: Option<u64>) {
+        *self.max_disk_usage.lock().unwrap() = max_disk_usage;
+    }
+
+    fn get_max_disk_usage(&self) -> Option<u64> {
+        *self.max_disk_usage.lock().unwrap()
+    }
+
+    fn set_format(&self, format: LogFormat) {
+        *self.format.lock().unwrap() = format;
+    }
+
+    fn get_format(&self) -> LogFormat {
+        *self.format.lock().unwrap()
+    }
+
+    fn set_target_filters(&self, filters: Vec<(TargetPattern, log::LevelFilter)>) {
+        *self.target_filters.lock().unwrap() = filters;
+        self.recompute_max_level();
+    }
+
+    /// Returns the first matching per-target override, if any, in registration order.
+    fn target_override(&self, target: &str) -> Option<log::LevelFilter> {
+        self.target_filters
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(pattern, _)| pattern.matches(target))
+            .map(|(_, level)| *level)
+    }
+
+    /// The `log` crate filters out disabled records before `Log::enabled` is
+    /// even called, using a single global max level. Target overrides can
+    /// raise the effective level above `stdout_level`/`file_level`, so that
+    /// global max has to track the loosest of all of them.
+    fn recompute_max_level(&self) {
+        let mut max = (*self.stdout_level.lock().unwrap()).max(*self.file_level.lock().unwrap());
+        for (_, level) in self.target_filters.lock().unwrap().iter() {
+            max = max.max(*level);
+        }
+        log::set_max_level(max);
+    }
+}
+
+/// Matches a log record's `target()` against either an exact string or a
+/// regular expression, e.g. `"vending"` or `"^pay.*"`. A pattern is only
+/// compiled as a regex when it's explicitly anchored with a leading `^` or
+/// trailing `$` — otherwise it's matched literally, so a target containing a
+/// plain `.` (a common namespacing character, e.g. `"order.service"`) can't
+/// silently turn into "match any character".
+enum TargetPattern {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl TargetPattern {
+    fn parse(pattern: &str) -> Self {
+        if !pattern.starts_with('^') && !pattern.ends_with('$') {
+            return TargetPattern::Exact(pattern.to_string());
+        }
+
+        match Regex::new(pattern) {
+            Ok(re) => TargetPattern::Regex(re),
+            Err(_) => TargetPattern::Exact(pattern.to_string()),
+        }
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        match self {
+            TargetPattern::Exact(exact) => exact == target,
+            TargetPattern::Regex(re) => re.is_match(target),
+        }
     }
 }
 
+/// Sets the total-disk-usage budget (in bytes) for `log_*.log`/`order_*.log`
+/// files under the logger's `base_path`. Enforced after every file write by
+/// deleting the least-recently-modified files first until usage is back
+/// under budget. `None` disables the budget (the default).
+pub fn set_max_disk_usage(max_disk_usage: Option<u64>) {
+    LOGGER.set_max_disk_usage(max_disk_usage);
+}
+
+/// Registers per-target level overrides, consulted before the global
+/// `stdout_level`/`file_level` to decide whether a record for a given
+/// `target()` is emitted. Rules are tried in order; the first match wins.
+/// A pattern is matched as an exact string unless it's explicitly anchored
+/// with a leading `^` or trailing `$`, in which case it's compiled as a
+/// regex (e.g. `"^pay.*"`); see [`TargetPattern`].
+pub fn set_target_filters(filters: &[(&str, log::LevelFilter)]) {
+    let parsed = filters
+        .iter()
+        .map(|(pattern, level)| (TargetPattern::parse(pattern), *level))
+        .collect();
+    LOGGER.set_target_filters(parsed);
+}
+
+/// Parses per-target level overrides out of an env var formatted like
+/// `env_logger` directives, e.g. `DAILY_LOG_FILTER=vending=debug,ui=warn`.
+/// Each target is parsed the same way as [`set_target_filters`]: matched as
+/// an exact string unless explicitly anchored with a leading `^` or
+/// trailing `$`, in which case it's compiled as a regex. Malformed rules are
+/// skipped. Does nothing if `var_name` isn't set.
+pub fn set_target_filters_from_env(var_name: &str) {
+    let Ok(value) = std::env::var(var_name) else {
+        return;
+    };
+
+    let parsed = value
+        .split(',')
+        .filter_map(|rule| {
+            let (pattern, level) = rule.split_once('=')?;
+            let level: log::LevelFilter = level.trim().parse().ok()?;
+            Some((TargetPattern::parse(pattern.trim()), level))
+        })
+        .collect();
+
+    LOGGER.set_target_filters(parsed);
+}
+
 impl Log for DailyLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= log::max_level()
+        let global = (*self.stdout_level.lock().unwrap()).max(*self.file_level.lock().unwrap());
+        let effective = self.target_override(metadata.target()).unwrap_or(global);
+        metadata.level() <= effective
     }
 
     fn log(&self, record: &Record) {
@@ -64,69 +376,174 @@ impl Log for DailyLogger {
             return;
         }
 
-        let stdout_level = *self.stdout_level.lock().unwrap();
-        let file_level = *self.file_level.lock().unwrap();
+        let override_level = self.target_override(record.target());
+        let stdout_level = override_level.unwrap_or(*self.stdout_level.lock().unwrap());
+        let file_level = override_level.unwrap_or(*self.file_level.lock().unwrap());
 
         let now = Local::now();
-        let mut log_entry: String = format!(
-            "{}-{}|[{}]: {}",
-            now.to_rfc3339(),
-            record.level(),
-            record.target(),
-            record.args()
-        );
-
-
-        
         let key_values = record.key_values();
-        if let Some(uuid) = key_values.get(Key::from("uuid")) {
-            let file_name = format!("order_{uuid}.log");
+        let uuid = key_values.get(Key::from("uuid")).map(|v| v.to_string());
 
-            log_entry = format!(
+        let text_entry = match &uuid {
+            Some(uuid) => format!(
                 "{}-{}|[{}]<{}>:{}",
                 now.to_rfc3339(),
                 record.level(),
                 record.target(),
                 uuid,
                 record.args()
-            );
-
-            if record.level() <= file_level {
-                write_to_file(&file_name, &log_entry, self.get_base_path());
-            }
-        }
+            ),
+            None => format!(
+                "{}-{}|[{}]: {}",
+                now.to_rfc3339(),
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+        };
 
         if record.level() <= stdout_level {
             let colored_entry = match record.level() {
-                Level::Error => format!("\x1b[31m{log_entry}\x1b[0m"),
-                Level::Warn => format!("\x1b[33m{log_entry}\x1b[0m"),
-                Level::Info => format!("\x1b[32m{log_entry}\x1b[0m"),
-                Level::Debug => format!("\x1b[37m{log_entry}\x1b[0m"),
-                Level::Trace => format!("\x1b[90m{log_entry}\x1b[0m"),
+                Level::Error => format!("\x1b[31m{text_entry}\x1b[0m"),
+                Level::Warn => format!("\x1b[33m{text_entry}\x1b[0m"),
+                Level::Info => format!("\x1b[32m{text_entry}\x1b[0m"),
+                Level::Debug => format!("\x1b[37m{text_entry}\x1b[0m"),
+                Level::Trace => format!("\x1b[90m{text_entry}\x1b[0m"),
             };
             println!("{colored_entry}");
         }
 
         if record.level() <= file_level {
+            let dedup_key = match &uuid {
+                Some(uuid) => format!("{}|[{}]<{}>:{}", record.level(), record.target(), uuid, record.args()),
+                None => format!("{}|[{}]:{}", record.level(), record.target(), record.args()),
+            };
+
+            let file_entry = match self.get_format() {
+                LogFormat::Text => text_entry,
+                LogFormat::Json => json_log_entry(record, now),
+            };
+
+            let base_path = self.get_base_path();
+            let mut written_paths = Vec::with_capacity(2);
+
+            if let Some(uuid) = &uuid {
+                let file_name = format!("order_{uuid}.log");
+                written_paths.push(write_to_file(&file_name, &file_entry, &dedup_key, base_path.clone()));
+            }
+
             let date_log_name = format!("log_{}_{}_{}.log", now.year(), now.month(), now.day());
-            write_to_file(&date_log_name, &log_entry, self.get_base_path());
+            written_paths.push(write_to_file(&date_log_name, &file_entry, &dedup_key, base_path.clone()));
+
+            if let (Some(max_disk_usage), Some(base_path)) = (self.get_max_disk_usage(), base_path) {
+                enforce_disk_budget_at(&base_path, max_disk_usage, &written_paths);
+            }
         }
     }
 
     fn flush(&self) {}
 }
 
-fn write_to_file(file_name: &str, log_entry: &str, base_path: Option<PathBuf>) {
+/// Output mode for file writes. Stdout always stays in the colored text
+/// format regardless of this setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The original hand-formatted `"{rfc3339}-{level}|[{target}]<{uuid}>:{msg}"` line.
+    Text,
+    /// Newline-delimited JSON objects, one per log line, suitable for machine parsing.
+    Json,
+}
+
+/// Selects the file output format used by every subsequent log write.
+/// Defaults to [`LogFormat::Text`].
+pub fn set_log_format(format: LogFormat) {
+    LOGGER.set_format(format);
+}
+
+fn json_log_entry(record: &Record, now: chrono::DateTime<Local>) -> String {
+    let mut fields = serde_json::Map::new();
+    let _ = record.key_values().visit(&mut JsonFieldsVisitor { map: &mut fields });
+
+    let entry = serde_json::json!({
+        "timestamp": now.to_rfc3339(),
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+        "fields": fields,
+    });
+
+    entry.to_string()
+}
+
+struct JsonFieldsVisitor<'a> {
+    map: &'a mut serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'kvs, 'a> kv::VisitSource<'kvs> for JsonFieldsVisitor<'a> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.map.insert(key.to_string(), serde_json::Value::String(value.to_string()));
+        Ok(())
+    }
+}
+
+/// Default per-file byte cap before a log file is rolled over, mirroring the
+/// Fuchsia `log_listener`'s default.
+const DEFAULT_FILE_CAPACITY: u64 = 64 * 1024;
+/// Default number of rotated backups (`.1`, `.2`, ...) kept per file.
+const DEFAULT_ROTATION_KEEP: usize = 5;
+
+/// Overrides the per-file rotation byte cap and keep-count used by every file
+/// in the cache. Call before logging starts; defaults to
+/// [`DEFAULT_FILE_CAPACITY`] / [`DEFAULT_ROTATION_KEEP`] otherwise.
+pub fn set_file_rotation(capacity: u64, keep: usize) {
     let mut cache = FILE_CACHE.lock().unwrap();
+    cache.file_capacity = capacity;
+    cache.rotation_keep = keep.max(1);
+}
+
+fn write_to_file(file_name: &str, log_entry: &str, dedup_key: &str, base_path: Option<PathBuf>) -> PathBuf {
     let full_path = base_path.map(|base| base.join(file_name)).unwrap_or_else(|| PathBuf::from(file_name));
-    let writer = cache.get_or_open(full_path);
-    let _ = writeln!(writer, "{log_entry}");
-    let _ = writer.flush();
+
+    let mut cache = FILE_CACHE.lock().unwrap();
+    cache.write_line(full_path.clone(), log_entry, dedup_key);
+
+    full_path
+}
+
+/// Overrides the duplicate-line suppression window used by every file in the
+/// cache. `Some(n)` fully suppresses a run of consecutive lines that hash the
+/// same (minus the timestamp) after the first, emitting a trailing
+/// `"(last message repeated K times)"` summary when a different line finally
+/// arrives or the file rotates. A run longer than `n` lines is split into
+/// multiple summaries rather than staying silent indefinitely: every `n`th
+/// repeat flushes a `"(last message repeated n times)"` summary early and
+/// starts a fresh count. `None` disables dedup (the default), so every line
+/// is written as-is.
+pub fn set_dedup_window(window: Option<usize>) {
+    let mut cache = FILE_CACHE.lock().unwrap();
+    cache.dedup_window = window;
+}
+
+fn hash_str(s: &str) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    s.hash(&mut hasher);
+    hasher.finish()
+}
+
+struct CachedFile {
+    writer: BufWriter<File>,
+    len: u64,
+    last_key: Option<u64>,
+    repeat_count: usize,
 }
 
 struct FileCache {
     max_size: usize,
-    files: HashMap<PathBuf, BufWriter<File>>,
+    file_capacity: u64,
+    rotation_keep: usize,
+    dedup_window: Option<usize>,
+    files: HashMap<PathBuf, CachedFile>,
     order: VecDeque<PathBuf>,
 }
 
@@ -134,12 +551,15 @@ impl FileCache {
     fn new(max_size: usize) -> Self {
         Self {
             max_size,
+            file_capacity: DEFAULT_FILE_CAPACITY,
+            rotation_keep: DEFAULT_ROTATION_KEEP,
+            dedup_window: None,
             files: HashMap::new(),
             order: VecDeque::new(),
         }
     }
 
-    fn get_or_open(&mut self, path: PathBuf) -> &mut BufWriter<File> {
+    fn get_or_open(&mut self, path: PathBuf) -> &mut CachedFile {
         if self.files.contains_key(&path) {
             self.order.retain(|f| f != &path);
             self.order.push_back(path.clone());
@@ -160,13 +580,108 @@ impl FileCache {
                 .open(&path)
                 .expect("Failed to open log file");
 
+            let len = file.metadata().map(|m| m.len()).unwrap_or(0);
             let writer = BufWriter::with_capacity(1024, file);
-            self.files.insert(path.clone(), writer);
+            self.files.insert(
+                path.clone(),
+                CachedFile { writer, len, last_key: None, repeat_count: 0 },
+            );
             self.order.push_back(path.clone());
         }
 
         self.files.get_mut(&path).unwrap()
     }
+
+    /// Drops the cached writer for `path`, if any, so a file that's about to be
+    /// deleted or rotated isn't reused after it disappears out from under us.
+    fn evict(&mut self, path: &Path) {
+        self.files.remove(path);
+        self.order.retain(|f| f != path);
+    }
+
+    fn write_line(&mut self, path: PathBuf, log_entry: &str, dedup_key: &str) {
+        if self.files.get(&path).is_some_and(|f| f.len >= self.file_capacity) {
+            self.rotate(&path);
+        }
+
+        let dedup_window = self.dedup_window;
+        let cached = self.get_or_open(path);
+
+        if let Some(window) = dedup_window {
+            let key = hash_str(dedup_key);
+
+            if cached.last_key == Some(key) {
+                // Still the same line as last time: never re-emit it, just keep
+                // the run going until a different line shows up. `window` only
+                // bounds how long we'll stay silent before flushing a running
+                // summary, so a never-ending repeat doesn't hide forever.
+                cached.repeat_count += 1;
+
+                if cached.repeat_count >= window {
+                    let summary = format!("(last message repeated {} times)", cached.repeat_count);
+                    let _ = writeln!(cached.writer, "{summary}");
+                    let _ = cached.writer.flush();
+                    cached.len += summary.len() as u64 + 1;
+                    cached.repeat_count = 0;
+                }
+
+                return;
+            }
+
+            // A different line arrived: flush whatever run we were suppressing.
+            if cached.repeat_count > 0 {
+                let summary = format!("(last message repeated {} times)", cached.repeat_count);
+                let _ = writeln!(cached.writer, "{summary}");
+                cached.len += summary.len() as u64 + 1;
+                cached.repeat_count = 0;
+            }
+
+            cached.last_key = Some(key);
+        }
+
+        let _ = writeln!(cached.writer, "{log_entry}");
+        let _ = cached.writer.flush();
+        cached.len += log_entry.len() as u64 + 1;
+    }
+
+    /// Renames `path` to `path.1`, shifting any existing `.1..N` backups up by
+    /// one and dropping whatever falls off the end of the keep-count, then
+    /// leaves `path` free for a fresh file to be opened on the next write.
+    fn rotate(&mut self, path: &Path) {
+        // Flush any pending "repeated N times" summary before the writer for
+        // the old file is dropped, so a suppressed run in progress isn't
+        // silently lost when the size cap trips mid-run.
+        if let Some(cached) = self.files.get_mut(path) {
+            if cached.repeat_count > 0 {
+                let summary = format!("(last message repeated {} times)", cached.repeat_count);
+                let _ = writeln!(cached.writer, "{summary}");
+                let _ = cached.writer.flush();
+                cached.repeat_count = 0;
+            }
+        }
+
+        self.evict(path);
+
+        let oldest = rotated_path(path, self.rotation_keep);
+        if oldest.exists() {
+            let _ = fs::remove_file(&oldest);
+        }
+
+        for i in (1..self.rotation_keep).rev() {
+            let from = rotated_path(path, i);
+            if from.exists() {
+                let _ = fs::rename(&from, rotated_path(path, i + 1));
+            }
+        }
+
+        let _ = fs::rename(path, rotated_path(path, 1));
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
 }
 
 #[cfg(test)]
@@ -402,6 +917,212 @@ mod tests {
         cleanup_test_dir(&test_base);
     }
 
+    #[test]
+    fn test_cleanup_logs_removes_stale_files() {
+        let (test_base, _guard) = setup_test_dir("cleanup");
+
+        let stale_uuid = "stale-order";
+        info!(target: "cleanup_test", uuid = stale_uuid; "Message that should be pruned");
+        wait_for_file_operations();
+
+        let stale_file = test_base.join(format!("order_{}.log", stale_uuid));
+        assert!(stale_file.exists(), "Stale order log should exist before cleanup");
+
+        cleanup_logs(Duration::from_secs(0));
+        assert!(!stale_file.exists(), "Stale order log should be removed by cleanup");
+
+        let fresh_uuid = "fresh-order";
+        info!(target: "cleanup_test", uuid = fresh_uuid; "Message that should survive");
+        wait_for_file_operations();
+
+        let fresh_file = test_base.join(format!("order_{}.log", fresh_uuid));
+        cleanup_logs(Duration::from_secs(3600));
+        assert!(fresh_file.exists(), "Fresh order log should survive cleanup");
+
+        cleanup_test_dir(&test_base);
+    }
+
+    #[test]
+    fn test_file_rotation_on_size_cap() {
+        let (test_base, _guard) = setup_test_dir("rotation");
+        set_file_rotation(256, 2);
+
+        let rotation_uuid = "rotation-order";
+        for i in 0..50 {
+            info!(target: "rotation_test", uuid = rotation_uuid; "Rotation filler message {}", i);
+        }
+        wait_for_file_operations();
+
+        let base_file = test_base.join(format!("order_{}.log", rotation_uuid));
+        let first_backup = PathBuf::from(format!("{}.1", base_file.display()));
+
+        assert!(base_file.exists(), "Active order log should exist after rotation");
+        assert!(first_backup.exists(), "Rotated backup should exist once the cap is crossed");
+
+        set_file_rotation(DEFAULT_FILE_CAPACITY, DEFAULT_ROTATION_KEEP);
+        cleanup_test_dir(&test_base);
+    }
+
+    #[test]
+    fn test_is_log_file_recognizes_rotated_backups() {
+        assert!(is_log_file("order_abc.log"));
+        assert!(is_log_file("log_2024_1_5.log"));
+        assert!(is_log_file("order_abc.log.1"));
+        assert!(is_log_file("log_2024_1_5.log.12"));
+        assert!(!is_log_file("order_abc.log.bak"));
+        assert!(!is_log_file("notes.txt"));
+    }
+
+    #[test]
+    fn test_cleanup_and_budget_reclaim_rotated_backups() {
+        let (test_base, _guard) = setup_test_dir("rotation_reclaim");
+        fs::create_dir_all(&test_base).unwrap();
+
+        // Manufacture a rotated backup directly rather than driving real writes
+        // through rotation: the daily log file rotates on the same byte cap as
+        // the order file, so racing real writes against each other for mtime
+        // order would make which file gets evicted nondeterministic.
+        let base_file = test_base.join("order_rotation-reclaim.log");
+        let backup_file = PathBuf::from(format!("{}.1", base_file.display()));
+        fs::write(&backup_file, "stale rotated content").unwrap();
+        fs::write(&base_file, "fresh content").unwrap();
+
+        let old_mtime = SystemTime::now() - Duration::from_secs(3600);
+        File::open(&backup_file).unwrap().set_modified(old_mtime).unwrap();
+
+        let total: u64 = fs::read_dir(&test_base)
+            .unwrap()
+            .flatten()
+            .map(|e| e.metadata().unwrap().len())
+            .sum();
+        enforce_disk_budget_at(&test_base, total - 1, &[]);
+        assert!(!backup_file.exists(), "Disk budget enforcement should count and evict rotated backups");
+        assert!(base_file.exists(), "Disk budget enforcement should not evict the active file");
+
+        fs::write(&backup_file, "stale rotated content").unwrap();
+        File::open(&backup_file).unwrap().set_modified(old_mtime).unwrap();
+
+        cleanup_logs_at(&test_base, Duration::from_secs(1800));
+        assert!(!backup_file.exists(), "Retention sweep should also reclaim rotated backups");
+        assert!(base_file.exists(), "Retention sweep should not prune the fresh active file");
+
+        cleanup_test_dir(&test_base);
+    }
+
+    #[test]
+    fn test_max_disk_usage_evicts_oldest_files() {
+        let (test_base, _guard) = setup_test_dir("disk_budget");
+
+        for i in 0..3 {
+            let uuid = format!("disk-budget-{}", i);
+            info!(target: "disk_budget_test", uuid = uuid.as_str(); "First message for {}", uuid);
+            wait_for_file_operations();
+        }
+
+        let oldest_file = test_base.join("order_disk-budget-0.log");
+        let newest_file = test_base.join("order_disk-budget-2.log");
+        let total: u64 = fs::read_dir(&test_base)
+            .unwrap()
+            .flatten()
+            .map(|e| e.metadata().unwrap().len())
+            .sum();
+
+        set_max_disk_usage(Some(total - 1));
+        info!(target: "disk_budget_test", uuid = "disk-budget-2"; "Second message to trigger enforcement");
+        wait_for_file_operations();
+
+        assert!(!oldest_file.exists(), "Oldest order log should be evicted once the disk budget is exceeded");
+        assert!(newest_file.exists(), "Newest order log should survive disk budget enforcement");
+
+        set_max_disk_usage(None);
+        cleanup_test_dir(&test_base);
+    }
+
+    #[test]
+    fn test_json_log_format() {
+        let (test_base, _guard) = setup_test_dir("json");
+        set_log_format(LogFormat::Json);
+
+        let json_uuid = "json-order";
+        info!(target: "json_test", uuid = json_uuid, order_id = 42; "Message in json format");
+        wait_for_file_operations();
+
+        let order_file = test_base.join(format!("order_{}.log", json_uuid));
+        let content = fs::read_to_string(&order_file).expect("Should read json order file");
+        let line = content.lines().next().expect("Should have a json line");
+        let parsed: serde_json::Value = serde_json::from_str(line).expect("Line should be valid json");
+
+        assert_eq!(parsed["level"], "INFO");
+        assert_eq!(parsed["target"], "json_test");
+        assert_eq!(parsed["message"], "Message in json format");
+        assert_eq!(parsed["fields"]["uuid"], json_uuid);
+        assert_eq!(parsed["fields"]["order_id"], "42");
+
+        set_log_format(LogFormat::Text);
+        cleanup_test_dir(&test_base);
+    }
+
+    #[test]
+    fn test_per_target_level_override() {
+        let (test_base, _guard) = setup_test_dir("target_filter");
+        set_target_filters(&[("vending", log::LevelFilter::Off), ("^pay.*", log::LevelFilter::Error)]);
+
+        info!(target: "vending", "Should be suppressed by the vending override");
+        info!(target: "payment_gateway", "Should be suppressed, info below the payments override");
+        error!(target: "payment_gateway", "Should pass the payments override");
+        info!(target: "ui", "Should pass via the global file level");
+
+        wait_for_file_operations();
+
+        let now = Local::now();
+        let daily_log = test_base.join(format!("log_{}_{}_{}.log", now.year(), now.month(), now.day()));
+        let content = fs::read_to_string(&daily_log).expect("Should read daily log");
+
+        assert!(!content.contains("Should be suppressed by the vending override"));
+        assert!(!content.contains("Should be suppressed, info below the payments override"));
+        assert!(content.contains("Should pass the payments override"));
+        assert!(content.contains("Should pass via the global file level"));
+
+        set_target_filters(&[]);
+        cleanup_test_dir(&test_base);
+    }
+
+    #[test]
+    fn test_target_pattern_literal_dot_is_not_a_wildcard() {
+        let exact = TargetPattern::parse("order.service");
+        assert!(exact.matches("order.service"));
+        assert!(!exact.matches("orderXservice"));
+
+        let regex = TargetPattern::parse("^order.service$");
+        assert!(regex.matches("order.service"));
+        assert!(regex.matches("orderXservice"));
+    }
+
+    #[test]
+    fn test_dedup_collapses_repeated_lines() {
+        let (test_base, _guard) = setup_test_dir("dedup");
+        set_dedup_window(Some(3));
+
+        let dedup_uuid = "dedup-order";
+        for _ in 0..5 {
+            error!(target: "dedup_test", uuid = dedup_uuid; "Identical tight-loop error");
+        }
+        info!(target: "dedup_test", uuid = dedup_uuid; "A distinct message");
+
+        wait_for_file_operations();
+
+        let order_file = test_base.join(format!("order_{}.log", dedup_uuid));
+        let content = fs::read_to_string(&order_file).expect("Should read dedup order file");
+
+        let occurrences = content.matches("Identical tight-loop error").count();
+        assert_eq!(occurrences, 1, "Only the first copy of a repeated line should ever be written, got {} copies", occurrences);
+        assert!(content.contains("repeated"), "A repeat summary should be written: {content}");
+        assert!(content.contains("A distinct message"), "A differing line should still be written in full");
+
+        set_dedup_window(None);
+        cleanup_test_dir(&test_base);
+    }
+
     #[test]
     fn test_directory_creation() {
         let (test_base, _guard) = setup_test_dir("directory");